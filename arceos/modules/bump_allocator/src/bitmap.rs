@@ -0,0 +1,319 @@
+//! A reclaimable, tree-structured bitmap page allocator.
+//!
+//! Unlike [`EarlyAllocator`], which can only hand pages out and never takes
+//! them back, this allocator is meant to take over the avail-area once the
+//! kernel is up and supports a working `dealloc_pages`.
+//!
+//! The free map is kept as a multi-level tree to stay compact and keep the
+//! hot path O(log n). Every internal node holds a `u32 summary` whose bit *i*
+//! is set iff child *i* is completely full, plus an array of 32 children. The
+//! leaf level collapses to a plain `u32` in which each bit maps to one page
+//! (set = used). `alloc_pages` descends from the root, at each level picking
+//! the first child that still has a free slot (the first *clear* summary bit,
+//! found with `trailing_ones`), and on the way back up only marks a parent's
+//! summary bit once the child has become completely full. `dealloc_pages`
+//! clears the leaf bit and then clears ancestor summary bits back up to the
+//! root.
+//!
+//! [`EarlyAllocator`]: crate::EarlyAllocator
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use allocator::{AllocError, AllocResult, BaseAllocator, PageAllocator};
+
+/// Fan-out of every tree node; also the number of pages covered by one leaf.
+const ORDER: usize = 32;
+
+/// One node of the free tree.
+///
+/// Whether a node is a leaf is given by its level in the tree (level 0).
+/// For an internal node, `summary` bit *i* is set iff `children[i]` is full,
+/// and `leaf` is unused. For a leaf node, `children` stay `None` and `leaf`
+/// is the per-page bitmap where bit *i* set means the page is in use.
+struct Node {
+    summary: u32,
+    leaf: u32,
+    children: [Option<Box<Node>>; ORDER],
+}
+
+impl Node {
+    const NONE: Option<Box<Node>> = None;
+
+    fn new() -> Self {
+        Self {
+            summary: 0,
+            leaf: 0,
+            children: [Self::NONE; ORDER],
+        }
+    }
+
+    /// Whether this node has no free slot left.
+    fn is_full(&self, is_leaf: bool) -> bool {
+        if is_leaf {
+            self.leaf == u32::MAX
+        } else {
+            self.summary == u32::MAX
+        }
+    }
+}
+
+/// A reclaimable page allocator backed by a tree of bitmaps.
+///
+/// `PAGE_SIZE` must be a power of two. On `init` the managed range is shrunk
+/// to whole pages: its start is rounded up and its end rounded down to
+/// `PAGE_SIZE`.
+pub struct BitmapPageAllocator<const PAGE_SIZE: usize> {
+    base: usize,
+    total_pages: usize,
+    used_pages: usize,
+    /// Number of tree levels above the leaf (0 == a single leaf `u32`).
+    levels: usize,
+    root: Node,
+}
+
+impl<const PAGE_SIZE: usize> BitmapPageAllocator<PAGE_SIZE> {
+    /// Creates an empty allocator; call [`BaseAllocator::init`] before use.
+    pub const fn new() -> Self {
+        Self {
+            base: 0,
+            total_pages: 0,
+            used_pages: 0,
+            levels: 0,
+            root: Node {
+                summary: 0,
+                leaf: 0,
+                children: [Node::NONE; ORDER],
+            },
+        }
+    }
+
+    /// Number of pages spanned by one child of a node at `level`.
+    ///
+    /// A leaf node (`level == 0`) holds 32 pages in its `u32`, so each child of
+    /// a level-1 node spans `ORDER^1 = 32` pages, a child of a level-2 node
+    /// spans `ORDER^2` pages, and so on — the child stride at `level` is
+    /// `ORDER^level`.
+    fn span(level: usize) -> usize {
+        ORDER.pow(level as u32)
+    }
+
+    /// Allocates a single free page, returning its index relative to the
+    /// start of the arena.
+    ///
+    /// Descends the tree picking the first non-full child at each level.
+    /// `base_page` is the base of the arena in pages, so alignment is checked
+    /// against the absolute page number `base_page + page` rather than the
+    /// index 0.
+    fn alloc_one(
+        node: &mut Node,
+        level: usize,
+        page_align: usize,
+        base_page: usize,
+        offset: usize,
+    ) -> Option<usize> {
+        if level == 0 {
+            // Leaf: find the first free bit whose absolute address is aligned.
+            let mut bits = node.leaf;
+            while bits != u32::MAX {
+                let i = bits.trailing_ones() as usize;
+                let page = offset + i;
+                if (base_page + page) & ((1 << page_align) - 1) == 0 {
+                    node.leaf |= 1 << i;
+                    return Some(page);
+                }
+                bits |= 1 << i;
+            }
+            return None;
+        }
+
+        let span = Self::span(level);
+        let mut summary = node.summary;
+        while summary != u32::MAX {
+            let i = summary.trailing_ones() as usize;
+            let child_off = offset + i * span;
+            let child = node.children[i].get_or_insert_with(|| Box::new(Node::new()));
+            if let Some(page) = Self::alloc_one(child, level - 1, page_align, base_page, child_off) {
+                if child.is_full(level - 1 == 0) {
+                    node.summary |= 1 << i;
+                }
+                return Some(page);
+            }
+            // Child had no suitably-aligned slot; mark it tried and move on.
+            summary |= 1 << i;
+        }
+        None
+    }
+
+    /// Clears the used bit for `page`, clearing ancestor summary bits too.
+    fn dealloc_one(node: &mut Node, level: usize, page: usize, offset: usize) {
+        if level == 0 {
+            let i = page - offset;
+            node.leaf &= !(1 << i);
+            return;
+        }
+        let span = Self::span(level);
+        let i = (page - offset) / span;
+        if let Some(child) = node.children[i].as_mut() {
+            Self::dealloc_one(child, level - 1, page, offset + i * span);
+            // The child now has at least one free slot, so clear its summary bit.
+            node.summary &= !(1 << i);
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> Default for BitmapPageAllocator<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_SIZE: usize> BaseAllocator for BitmapPageAllocator<PAGE_SIZE> {
+    fn init(&mut self, start: usize, size: usize) {
+        let base = (start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let end = (start + size) & !(PAGE_SIZE - 1);
+        self.base = base;
+        self.total_pages = end.saturating_sub(base) / PAGE_SIZE;
+        self.used_pages = 0;
+        self.root = Node::new();
+
+        // Pick the smallest tree that can index every page.
+        let mut levels = 0;
+        while Self::span(levels + 1) < self.total_pages {
+            levels += 1;
+        }
+        self.levels = levels;
+    }
+
+    fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
+        Err(AllocError::NoMemory)
+    }
+}
+
+impl<const PAGE_SIZE: usize> PageAllocator for BitmapPageAllocator<PAGE_SIZE> {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if num_pages == 0 || align_pow2 >= usize::BITS as usize {
+            return Err(AllocError::InvalidParam);
+        }
+        if num_pages > self.total_pages - self.used_pages {
+            return Err(AllocError::NoMemory);
+        }
+
+        // `align_pow2` is a byte-address shift (matching the sibling
+        // `EarlyAllocator` impl); convert it to an alignment of the page index
+        // and check it against the absolute page number, since `base` is only
+        // guaranteed `PAGE_SIZE`-aligned.
+        let page_align = align_pow2.saturating_sub(PAGE_SIZE.trailing_zeros() as usize);
+        let base_page = self.base / PAGE_SIZE;
+
+        // Fast path: a single page is served by descending the summary tree.
+        if num_pages == 1 {
+            if let Some(page) = Self::alloc_one(&mut self.root, self.levels, page_align, base_page, 0)
+            {
+                if page < self.total_pages {
+                    self.used_pages += 1;
+                    return Ok(self.base + page * PAGE_SIZE);
+                }
+                // Descent handed out a padding page past the end; take it back.
+                Self::dealloc_one(&mut self.root, self.levels, page, 0);
+            }
+            return Err(AllocError::NoMemory);
+        }
+
+        // For a multi-page request we grab a contiguous, suitably-aligned run.
+        let align_mask = (1 << page_align) - 1;
+        let align_pages = 1 << page_align;
+        let mut start = 0;
+        'scan: while start + num_pages <= self.total_pages {
+            let off = (base_page + start) & align_mask;
+            if off != 0 {
+                // Jump to the next absolute-aligned page.
+                start += align_pages - off;
+                continue;
+            }
+            for p in start..start + num_pages {
+                if self.is_page_used(p) {
+                    start = p + 1;
+                    continue 'scan;
+                }
+            }
+            for p in start..start + num_pages {
+                self.set_page(p, true);
+            }
+            self.used_pages += num_pages;
+            return Ok(self.base + start * PAGE_SIZE);
+        }
+        Err(AllocError::NoMemory)
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        assert!(pos >= self.base, "dealloc out of range");
+        let start = (pos - self.base) / PAGE_SIZE;
+        assert!(start + num_pages <= self.total_pages, "dealloc out of range");
+        for p in start..start + num_pages {
+            debug_assert!(self.is_page_used(p), "double free of page {p}");
+            self.set_page(p, false);
+        }
+        self.used_pages -= num_pages;
+    }
+
+    fn total_pages(&self) -> usize {
+        self.total_pages
+    }
+
+    fn used_pages(&self) -> usize {
+        self.used_pages
+    }
+
+    fn available_pages(&self) -> usize {
+        self.total_pages - self.used_pages
+    }
+}
+
+impl<const PAGE_SIZE: usize> BitmapPageAllocator<PAGE_SIZE> {
+    /// Marks `page` used or free, keeping the tree summaries in sync.
+    fn set_page(&mut self, page: usize, used: bool) {
+        if used {
+            Self::mark_used(&mut self.root, self.levels, page, 0);
+        } else {
+            Self::dealloc_one(&mut self.root, self.levels, page, 0);
+        }
+    }
+
+    fn is_page_used(&self, page: usize) -> bool {
+        let mut node = &self.root;
+        let mut level = self.levels;
+        let mut offset = 0;
+        loop {
+            if level == 0 {
+                return node.leaf & (1 << (page - offset)) != 0;
+            }
+            let span = Self::span(level);
+            let i = (page - offset) / span;
+            match node.children[i].as_ref() {
+                Some(child) => {
+                    node = child;
+                    offset += i * span;
+                    level -= 1;
+                }
+                None => return false,
+            }
+        }
+    }
+
+    fn mark_used(node: &mut Node, level: usize, page: usize, offset: usize) {
+        if level == 0 {
+            node.leaf |= 1 << (page - offset);
+            return;
+        }
+        let span = Self::span(level);
+        let i = (page - offset) / span;
+        let child = node.children[i].get_or_insert_with(|| Box::new(Node::new()));
+        Self::mark_used(child, level - 1, page, offset + i * span);
+        if child.is_full(level - 1 == 0) {
+            node.summary |= 1 << i;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! A [`GlobalAlloc`] adapter over [`EarlyAllocator`].
+//!
+//! Wrapping the early arena in a lock and the `alloc`-crate's `GlobalAlloc`
+//! convention lets it back `#[global_allocator]` during boot, so ordinary
+//! `alloc` collections work before the permanent allocator is installed —
+//! without every caller driving the [`ByteAllocator`] trait by hand.
+//!
+//! [`ByteAllocator`]: allocator::ByteAllocator
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use allocator::ByteAllocator;
+
+use crate::EarlyAllocator;
+
+/// A lock-wrapped [`EarlyAllocator`] usable as a `#[global_allocator]`.
+pub struct LockedEarly<const PAGE_SIZE: usize> {
+    inner: spin::Mutex<EarlyAllocator<PAGE_SIZE>>,
+}
+
+impl<const PAGE_SIZE: usize> LockedEarly<PAGE_SIZE> {
+    /// Creates an uninitialized adapter; `init` the inner allocator before use.
+    pub const fn new() -> Self {
+        Self {
+            inner: spin::Mutex::new(EarlyAllocator::new()),
+        }
+    }
+
+    /// Locks and returns the inner allocator for setup (e.g. `init`).
+    pub fn lock(&self) -> spin::MutexGuard<'_, EarlyAllocator<PAGE_SIZE>> {
+        self.inner.lock()
+    }
+}
+
+unsafe impl<const PAGE_SIZE: usize> GlobalAlloc for LockedEarly<PAGE_SIZE> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            // A zero-sized allocation gets a dangling-but-aligned pointer, as
+            // the `alloc` crate expects; it must never be dereferenced.
+            return layout.align() as *mut u8;
+        }
+        match self.inner.lock().alloc(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        if let Some(ptr) = ptr::NonNull::new(ptr) {
+            self.inner.lock().dealloc(ptr, layout);
+        }
+    }
+}
+
+impl<const PAGE_SIZE: usize> Default for LockedEarly<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
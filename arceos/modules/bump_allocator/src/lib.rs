@@ -7,6 +7,16 @@ use core::result;
 use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
 use log::info;
 
+mod bitmap;
+mod global;
+
+pub use bitmap::BitmapPageAllocator;
+pub use global::LockedEarly;
+
+/// Maximum number of spare memory regions retained for fallover after the
+/// primary double-ended arena is chosen.
+const MAX_REGIONS: usize = 4;
+
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
 /// This is a double-end memory range:
@@ -27,6 +37,14 @@ pub struct EarlyAllocator<const PAGE_SIZE: usize> {
     b_pos: usize,
     p_pos: usize,
     count: usize,
+    /// The most recently handed-out bytes block, as `(aligned start, b_pos
+    /// just after it)`. Used to roll `b_pos` back on a LIFO free. Cleared by
+    /// every free, matching or not, and on a region switch.
+    last: Option<(usize, usize)>,
+    /// Spare disjoint regions, as `(start, size)`, that the arena falls over
+    /// to once the primary range is exhausted.
+    extra: [(usize, usize); MAX_REGIONS],
+    extra_len: usize,
 }
 impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
     pub const fn new() -> Self {
@@ -36,12 +54,172 @@ impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
             b_pos: 0,
             p_pos: 0,
             count: 0,
+            last: None,
+            extra: [(0, 0); MAX_REGIONS],
+            extra_len: 0,
+        }
+    }
+
+    /// Initializes the arena from a memory map discovered at runtime (e.g. the
+    /// free regions parsed out of the devicetree handed to the kernel in `a1`).
+    ///
+    /// The largest region becomes the primary double-ended arena; the rest are
+    /// stashed for fallover, exactly as if passed to [`add_memory`]. Excess
+    /// regions beyond [`MAX_REGIONS`] are dropped.
+    ///
+    /// [`add_memory`]: BaseAllocator::add_memory
+    pub fn init_from_regions(&mut self, regions: impl Iterator<Item = (usize, usize)>) {
+        let mut largest: Option<(usize, usize)> = None;
+        let mut rest: [(usize, usize); MAX_REGIONS] = [(0, 0); MAX_REGIONS];
+        let mut rest_len = 0;
+
+        for (start, size) in regions {
+            if size == 0 {
+                continue;
+            }
+            match largest {
+                Some((_, best)) if size <= best => {
+                    if rest_len < MAX_REGIONS {
+                        rest[rest_len] = (start, size);
+                        rest_len += 1;
+                    }
+                }
+                _ => {
+                    // New largest; demote the previous one to the spare list.
+                    if let Some(prev) = largest.replace((start, size)) {
+                        if rest_len < MAX_REGIONS {
+                            rest[rest_len] = prev;
+                            rest_len += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let (start, size) = largest.expect("init_from_regions: no usable memory region");
+        self.init(start, size);
+        self.extra = rest;
+        self.extra_len = rest_len;
+    }
+
+    /// Re-points the arena at a spare region big enough to hold `required`
+    /// bytes, returning `false` if none qualifies.
+    ///
+    /// The current region's still-free tail `[b_pos, p_pos)` is recycled back
+    /// into the spare list (dropped only if the list is full), so an over-large
+    /// request that overflows a mostly-empty arena no longer abandons the rest
+    /// of it — the space is reusable once a request small enough to fit it
+    /// comes along. Bytes and pages already handed out stay valid; the early
+    /// allocator never reuses those. Only regions with `size >= required` are
+    /// adopted, which both avoids a pointless switch and guarantees the retry
+    /// loop terminates (each switch removes one qualifying region and can only
+    /// add back a smaller, non-qualifying one).
+    fn switch_region(&mut self, required: usize) -> bool {
+        // Pick the largest spare region that can actually satisfy the request.
+        let mut best = None;
+        let mut best_size = 0;
+        for i in 0..self.extra_len {
+            let size = self.extra[i].1;
+            if size >= required && size > best_size {
+                best = Some(i);
+                best_size = size;
+            }
         }
+        let best = match best {
+            Some(b) => b,
+            None => return false,
+        };
+        let (start, size) = self.extra[best];
+        self.extra_len -= 1;
+        self.extra[best] = self.extra[self.extra_len];
+
+        // Recycle the free tail of the region we're leaving.
+        let free = (self.b_pos, self.p_pos.saturating_sub(self.b_pos));
+        if free.1 > 0 && self.extra_len < MAX_REGIONS {
+            self.extra[self.extra_len] = free;
+            self.extra_len += 1;
+        }
+
+        self.start = start;
+        self.end = start + size;
+        self.b_pos = start;
+        self.p_pos = self.end;
+        self.last = None;
+        true
     }
 
     fn align_up(addr: usize, align: usize) -> usize {
         (addr + align - 1) & !(align - 1)
     }
+
+    /// Hands the still-free avail-area over to a permanent allocator.
+    ///
+    /// Returns `(b_pos, p_pos)`, the bounds of the untouched region between the
+    /// bytes-used and pages-used ends. A permanent allocator can `init` itself
+    /// on `[b_pos, p_pos)` to take over without disturbing the bytes and pages
+    /// already handed out by this early allocator.
+    pub fn handoff(&self) -> (usize, usize) {
+        (self.b_pos, self.p_pos)
+    }
+
+    /// Grows the block at `pos` from `old_layout` to `new_layout`.
+    ///
+    /// When `pos` is the most recently handed-out block this is an in-place
+    /// bump of `b_pos`, returning the same pointer — so a growable buffer that
+    /// repeatedly doubles never has to alloc-copy-free. Any other block falls
+    /// back to a fresh allocation and, per the allocator-wg contract, consumes
+    /// the old block: it is released here before returning, and its bytes are
+    /// left in place for the caller to copy from. Mirrors the allocator-wg
+    /// `grow`/`shrink` API shape.
+    pub fn grow(
+        &mut self,
+        pos: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
+        debug_assert!(new_layout.size() >= old_layout.size());
+        let aligned = pos.as_ptr() as usize;
+        if self.last == Some((aligned, self.b_pos)) && new_layout.align() <= old_layout.align() {
+            let delta = new_layout.size() - old_layout.size();
+            let new_b_pos = self.b_pos.checked_add(delta).ok_or(AllocError::InvalidParam)?;
+            if new_b_pos > self.p_pos {
+                return Err(AllocError::NoMemory);
+            }
+            self.b_pos = new_b_pos;
+            self.last = Some((aligned, new_b_pos));
+            return Ok(pos);
+        }
+        let new_ptr = self.alloc(new_layout)?;
+        // Consume the superseded block so `count` keeps tracking live blocks.
+        self.dealloc(pos, old_layout);
+        Ok(new_ptr)
+    }
+
+    /// Shrinks the block at `pos` from `old_layout` to `new_layout`.
+    ///
+    /// Counterpart to [`grow`](Self::grow): an in-place rollback of `b_pos` for
+    /// the last block, otherwise a fresh (smaller) allocation that likewise
+    /// consumes the old block (the old pointer is released here).
+    pub fn shrink(
+        &mut self,
+        pos: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
+        debug_assert!(new_layout.size() <= old_layout.size());
+        let aligned = pos.as_ptr() as usize;
+        if self.last == Some((aligned, self.b_pos)) && new_layout.align() <= old_layout.align() {
+            let delta = old_layout.size() - new_layout.size();
+            let new_b_pos = self.b_pos - delta;
+            self.b_pos = new_b_pos;
+            self.last = Some((aligned, new_b_pos));
+            return Ok(pos);
+        }
+        let new_ptr = self.alloc(new_layout)?;
+        // Consume the superseded block so `count` keeps tracking live blocks.
+        self.dealloc(pos, old_layout);
+        Ok(new_ptr)
+    }
 }
 
 impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
@@ -51,6 +229,8 @@ impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
         self.b_pos = start;
         self.p_pos = self.end;
         self.count = 0;
+        self.last = None;
+        self.extra_len = 0;
 
         info!(
             "[early_alloc] init: [{:#x}, {:#x}), total = {} KB",
@@ -60,7 +240,15 @@ impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
         );
     }
 
-    fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        if size == 0 {
+            return Ok(());
+        }
+        if self.extra_len >= MAX_REGIONS {
+            return Err(AllocError::NoMemory);
+        }
+        self.extra[self.extra_len] = (start, size);
+        self.extra_len += 1;
         Ok(())
     }
 }
@@ -70,22 +258,41 @@ impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
         let align = layout.align();
         let size = layout.size();
 
-        let aligned = Self::align_up(self.b_pos, align);
-        let new_b_pos = aligned.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        let mut aligned = Self::align_up(self.b_pos, align);
+        let mut new_b_pos = aligned.checked_add(size).ok_or(AllocError::InvalidParam)?;
 
-        if new_b_pos > self.p_pos {
-            return Err(AllocError::NoMemory);
+        // Once the current arena can't satisfy the request, fall over to the
+        // largest stored region big enough for it and retry there.
+        while new_b_pos > self.p_pos {
+            if !self.switch_region(size + align) {
+                return Err(AllocError::NoMemory);
+            }
+            aligned = Self::align_up(self.b_pos, align);
+            new_b_pos = aligned.checked_add(size).ok_or(AllocError::InvalidParam)?;
         }
 
         self.b_pos = new_b_pos;
         self.count += 1;
+        self.last = Some((aligned, new_b_pos));
 
         Ok(unsafe { NonNull::new_unchecked(aligned as *mut u8) })
     }
 
-    fn dealloc(&mut self, _pos: NonNull<u8>, _layout: Layout) {
+    fn dealloc(&mut self, pos: NonNull<u8>, _layout: Layout) {
         assert!(self.count > 0);
         self.count -= 1;
+
+        // LIFO fast path: if this is the block we handed out last and nothing
+        // has been allocated on top of it, give its space straight back by
+        // rolling `b_pos` down to the block's aligned start.
+        let aligned = pos.as_ptr() as usize;
+        if self.last == Some((aligned, self.b_pos)) {
+            self.b_pos = aligned;
+        }
+        // Either way the last-block record is now stale: a matching free
+        // consumed it, a non-matching free falls back to count-only accounting.
+        self.last = None;
+
         if self.count == 0 {
             self.b_pos = self.start;
         }
@@ -111,15 +318,21 @@ impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
         let size = num_pages * PAGE_SIZE;
         let align = 1 << align_pow2;
 
-        let mut new_p_pos = self.p_pos.checked_sub(size).ok_or(AllocError::InvalidParam)?;
-        new_p_pos &= !(align - 1);
+        loop {
+            let mut new_p_pos = self.p_pos.checked_sub(size).ok_or(AllocError::InvalidParam)?;
+            new_p_pos &= !(align - 1);
 
-        if new_p_pos < self.b_pos {
-            return Err(AllocError::NoMemory);
-        }
+            if new_p_pos >= self.b_pos {
+                self.p_pos = new_p_pos;
+                return Ok(self.p_pos);
+            }
 
-        self.p_pos = new_p_pos;
-        Ok(self.p_pos)
+            // Current arena exhausted; fall over to the largest stored region
+            // big enough for the request.
+            if !self.switch_region(size + align) {
+                return Err(AllocError::NoMemory);
+            }
+        }
     }
 
     fn dealloc_pages(&mut self, _pos: usize, _num_pages: usize) {